@@ -1,3 +1,6 @@
+use std::cmp::Ordering;
+use std::collections::HashMap;
+
 use actix_http::StatusCode;
 use actix_web::web::{self, Data};
 use actix_web::{HttpRequest, HttpResponse};
@@ -27,6 +30,8 @@ pub fn configure(cfg: &mut web::ServiceConfig) {
 struct SearchResults {
     #[serde(skip_serializing_if = "Option::is_none")]
     aggregate_hits: Option<Vec<SearchHitWithIndex>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    estimated_total_hits: Option<u64>,
     results: Vec<SearchResultWithIndex>,
 }
 
@@ -34,6 +39,16 @@ struct SearchResults {
 #[serde(rename_all = "camelCase")]
 struct SearchHitWithIndex {
     pub index_uid: String,
+    /// The score `merge_by_normalized_score` actually sorted on, i.e. `ranking_score * weight`.
+    /// Only set for that merge strategy, and only when the query's weight isn't the default `1.0`,
+    /// so clients can tell why a hit ranked differently than its raw `ranking_score` would suggest.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub weighted_ranking_score: Option<f64>,
+    /// The other indexes this hit's `dedupBy` key was also found in, when cross-index
+    /// deduplication collapsed several occurrences into this one. Empty when `dedupBy` isn't set
+    /// or the hit was unique.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub matched_index_uids: Vec<String>,
     #[serde(flatten)]
     pub hit: SearchHit,
 }
@@ -44,6 +59,31 @@ pub struct SearchQueries {
     queries: Vec<SearchQueryWithIndex>,
     #[deserr(default, error = DeserrJsonError<InvalidMultiSearchMergeStrategy>, default)]
     merge_strategy: MergeStrategy,
+    /// The `k` constant used by the `byReciprocalRank` merge strategy. Ignored by every other
+    /// strategy. Defaults to [`DEFAULT_RRF_K`].
+    #[deserr(default)]
+    k: Option<f64>,
+    /// Offset into the merged (federated) result set. Only meaningful alongside `merge_strategy`;
+    /// defaults to `0`.
+    #[deserr(default)]
+    offset: Option<usize>,
+    /// Number of merged hits to return. Only meaningful alongside `merge_strategy`; defaults to
+    /// the largest `limit`/`hitsPerPage` among `queries`.
+    #[deserr(default)]
+    limit: Option<usize>,
+    /// A document attribute used to recognize the same logical document across federated indexes
+    /// (e.g. a product mirrored in `products_en` and `products_fr`). For `byNormalizedScore` and
+    /// `byScoreDetails`, only the highest-scoring occurrence is kept in `aggregate_hits`. For
+    /// `byReciprocalRank`, matching occurrences are instead fused into a single, combined RRF
+    /// score, since rewarding a document that many indexes agree on is the point of RRF.
+    #[deserr(default)]
+    dedup_by: Option<String>,
+    /// Per-query weight used by `merge_by_normalized_score` to bias a federated search toward a
+    /// more authoritative index without re-tuning its ranking rules. Positional: `weights[i]`
+    /// applies to `queries[i]`. Missing or absent entries default to `1.0`. Ignored by every other
+    /// merge strategy.
+    #[deserr(default)]
+    weights: Option<Vec<f64>>,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Deserr, Default)]
@@ -53,6 +93,32 @@ pub enum MergeStrategy {
     None,
     ByNormalizedScore,
     ByScoreDetails,
+    ByReciprocalRank,
+}
+
+/// Default `k` constant of the Reciprocal Rank Fusion formula, as commonly used in information
+/// retrieval literature.
+const DEFAULT_RRF_K: f64 = 60.0;
+
+/// Computes the `(federation_offset, federation_limit, max_hits)` triple for the merged result
+/// set: the offset/limit window the caller wants, falling back to `0`/the widest single sub-query
+/// window (`per_query_limits`) when not explicit, plus `max_hits`, the total candidate count to
+/// over-fetch so the merge strategy has enough hits to fill that window.
+fn federation_window(
+    offset: Option<usize>,
+    limit: Option<usize>,
+    per_query_limits: &[usize],
+) -> (usize, usize, usize) {
+    let federation_offset = offset.unwrap_or(0);
+    let federation_limit =
+        limit.unwrap_or_else(|| per_query_limits.iter().copied().max().unwrap_or(0));
+    let max_hits = federation_offset + federation_limit;
+    (federation_offset, federation_limit, max_hits)
+}
+
+/// Slices `items` down to the requested `offset`/`limit` window.
+fn paginate<T>(items: Vec<T>, offset: usize, limit: usize) -> Vec<T> {
+    items.into_iter().skip(offset).take(limit).collect()
 }
 
 pub async fn multi_search_with_post(
@@ -61,29 +127,65 @@ pub async fn multi_search_with_post(
     req: HttpRequest,
     analytics: web::Data<dyn Analytics>,
 ) -> Result<HttpResponse, ResponseError> {
-    let SearchQueries { queries, merge_strategy } = params.into_inner();
-    // FIXME: REMOVE UNWRAP
-    let max_hits = queries
+    let SearchQueries { mut queries, merge_strategy, k, offset, limit, dedup_by, weights } =
+        params.into_inner();
+
+    // Align `weights` positionally with `queries`, defaulting any missing or absent entry to the
+    // neutral weight of `1.0`.
+    let weights_by_query: Vec<f64> = (0..queries.len())
+        .map(|i| weights.as_ref().and_then(|weights| weights.get(i).copied()).unwrap_or(1.0))
+        .collect();
+
+    // Each query's originally-requested hit count. Used both as the fallback for the federation
+    // window below and, after the over-fetch, to truncate `results[i].hits` back to what the
+    // caller actually asked for.
+    let original_hit_counts: Vec<usize> = queries
         .iter()
         .map(|SearchQueryWithIndex { limit, hits_per_page, .. }| hits_per_page.unwrap_or(*limit))
-        .max()
-        .unwrap();
+        .collect();
 
+    // The federation-level window the caller wants out of the merged hits. With no explicit
+    // `offset`/`limit`, this falls back to the widest single sub-query window, preserving the
+    // legacy behavior of returning, with no offset, up to the largest per-query `hitsPerPage` (or
+    // `limit` when `hitsPerPage` is unset) aggregate hits.
+    let (federation_offset, federation_limit, max_hits) =
+        federation_window(offset, limit, &original_hit_counts);
+
+    // Build the analytics aggregate from the queries as the caller actually sent them, before the
+    // over-fetch below inflates their `limit`/`hitsPerPage` for internal purposes: telemetry should
+    // reflect what was requested, not how much we fetched to satisfy the merge strategy.
     let mut multi_aggregate = MultiSearchAggregator::from_queries(&queries, &req);
 
+    // Over-fetch from every sub-query so the merge strategy has enough candidates to fill the
+    // requested federation window, even when a sub-query's own `limit`/`hitsPerPage` is smaller.
+    if merge_strategy != MergeStrategy::None {
+        for query in &mut queries {
+            query.limit = query.limit.max(max_hits);
+            if let Some(hits_per_page) = query.hits_per_page.as_mut() {
+                *hits_per_page = (*hits_per_page).max(max_hits);
+            }
+        }
+    }
+
     // Explicitly expect a `(ResponseError, usize)` for the error type rather than `ResponseError` only,
     // so that `?` doesn't work if it doesn't use `with_index`, ensuring that it is not forgotten in case of code
     // changes.
     let search_results: Result<_, (ResponseError, usize)> = (|| {
         async {
-            let mut search_results = Vec::with_capacity(queries.len());
-            for (query_index, (index_uid, mut query)) in
-                queries.into_iter().map(SearchQueryWithIndex::into_index_query).enumerate()
-            {
+            // Authorize and spawn every sub-query up front so they run concurrently: a federated
+            // search over N indexes should cost as much as the slowest index, not the sum of all of
+            // them. We still await the tasks in query order below so `search_results` stays
+            // positional for callers.
+            let mut tasks = Vec::with_capacity(queries.len());
+            for (query_index, query_with_index) in queries.into_iter().enumerate() {
+                let weight = weights_by_query[query_index];
+                let (index_uid, mut query) = query_with_index.into_index_query();
                 debug!("multi-search #{query_index}: called with params: {:?}", query);
 
                 // Check index from API key
                 if !index_scheduler.filters().is_index_authorized(&index_uid) {
+                    // Best-effort: skip whichever already-spawned sub-queries haven't started yet.
+                    abort_all(&tasks);
                     return Err(AuthenticationError::InvalidToken).with_index(query_index);
                 }
                 // Apply search rules from tenant token
@@ -102,18 +204,28 @@ pub async fn multi_search_with_post(
                         err.code = StatusCode::BAD_REQUEST;
                         err
                     })
-                    .with_index(query_index)?;
-                let search_result =
-                    tokio::task::spawn_blocking(move || perform_search(&index, query))
-                        .await
-                        .with_index(query_index)?;
+                    .with_index(query_index)
+                    .map_err(|err| {
+                        abort_all(&tasks);
+                        err
+                    })?;
+
+                let task = tokio::task::spawn_blocking(move || perform_search(&index, query));
+                tasks.push((query_index, index_uid, weight, task));
+            }
+
+            let mut search_results = Vec::with_capacity(tasks.len());
+            let mut weights = Vec::with_capacity(tasks.len());
+            for (query_index, index_uid, weight, task) in tasks {
+                let search_result = task.await.with_index(query_index)?;
 
                 search_results.push(SearchResultWithIndex {
                     index_uid: index_uid.into_inner(),
                     result: search_result.with_index(query_index)?,
                 });
+                weights.push(weight);
             }
-            Ok(search_results)
+            Ok((search_results, weights))
         }
     })()
     .await;
@@ -123,7 +235,7 @@ pub async fn multi_search_with_post(
     }
     analytics.post_multi_search(multi_aggregate);
 
-    let search_results = search_results.map_err(|(mut err, query_index)| {
+    let (mut search_results, weights) = search_results.map_err(|(mut err, query_index)| {
         // Add the query index that failed as context for the error message.
         // We're doing it only here and not directly in the `WithIndex` trait so that the `with_index` function returns a different type
         // of result and we can benefit from static typing.
@@ -135,17 +247,160 @@ pub async fn multi_search_with_post(
 
     let aggregate_hits = match merge_strategy {
         MergeStrategy::None => None,
-        MergeStrategy::ByScoreDetails => todo!(),
-        MergeStrategy::ByNormalizedScore => {
-            Some(merge_by_normalized_score(&search_results, max_hits))
+        MergeStrategy::ByScoreDetails => {
+            Some(merge_by_score_details(&search_results, dedup_by.as_deref(), max_hits))
         }
+        MergeStrategy::ByNormalizedScore => Some(merge_by_normalized_score(
+            &search_results,
+            &weights,
+            dedup_by.as_deref(),
+            max_hits,
+        )),
+        MergeStrategy::ByReciprocalRank => Some(merge_by_reciprocal_rank(
+            &search_results,
+            max_hits,
+            k.unwrap_or(DEFAULT_RRF_K),
+            dedup_by.as_deref(),
+        )),
     };
+    // Slice off the requested offset now that the merge strategy has produced up to
+    // `federation_offset + federation_limit` candidates.
+    let aggregate_hits = aggregate_hits.map(|hits| paginate(hits, federation_offset, federation_limit));
+    let estimated_total_hits = aggregate_hits.is_some().then(|| {
+        search_results
+            .iter()
+            .filter_map(|SearchResultWithIndex { result, .. }| result.estimated_total_hits)
+            .sum()
+    });
 
-    Ok(HttpResponse::Ok().json(SearchResults { aggregate_hits, results: search_results }))
+    // The merge strategies above need the full over-fetched hits as candidates, but `results` is
+    // the per-query response the caller asked for: truncate each one back to its original
+    // `limit`/`hitsPerPage` now that merging is done, so the over-fetch stays an internal
+    // implementation detail instead of leaking into this field.
+    if merge_strategy != MergeStrategy::None {
+        for (SearchResultWithIndex { result, .. }, &original_hits) in
+            search_results.iter_mut().zip(&original_hit_counts)
+        {
+            result.hits.truncate(original_hits);
+        }
+    }
+
+    Ok(HttpResponse::Ok().json(SearchResults { aggregate_hits, estimated_total_hits, results: search_results }))
 }
 
+/// Merges federated hits by `ranking_score`, weighted per sub-query so a more authoritative index
+/// can be biased ahead of the others without re-tuning its ranking rules. `weights` must be the
+/// same length as `search_results`, in the same order.
+///
+/// When `dedup_by` is set, hits are consumed in descending (weighted) score order, so the first
+/// occurrence of a given key is always the highest-scoring one; later occurrences are folded into
+/// it instead of appearing as separate hits, recorded in [`SearchHitWithIndex::matched_index_uids`].
 fn merge_by_normalized_score(
     search_results: &[SearchResultWithIndex],
+    weights: &[f64],
+    dedup_by: Option<&str>,
+    max_hits: usize,
+) -> Vec<SearchHitWithIndex> {
+    let mut iterators: Vec<_> = search_results
+        .iter()
+        .zip(weights.iter().copied())
+        .filter_map(|(SearchResultWithIndex { index_uid, result }, weight)| {
+            let mut it = result.hits.iter();
+            let next = it.next()?;
+            Some((index_uid, weight, it, next))
+        })
+        .collect();
+
+    let mut hits: Vec<SearchHitWithIndex> = Vec::with_capacity(max_hits);
+    let mut seen: HashMap<String, usize> = HashMap::new();
+
+    while hits.len() < max_hits && !iterators.is_empty() {
+        iterators.sort_by(|(_, a_weight, _, a), (_, b_weight, _, b)| {
+            let a_score = weighted_score(a.ranking_score, *a_weight);
+            let b_score = weighted_score(b.ranking_score, *b_weight);
+            a_score.partial_cmp(&b_score).unwrap_or(Ordering::Equal)
+        });
+
+        let Some((index_uid, weight, it, next)) = iterators.last_mut()
+        else {
+            break;
+        };
+
+        let index_uid = index_uid.clone();
+        let weight = *weight;
+        let hit = next.clone();
+        let key = dedup_by.and_then(|field| dedup_key(&hit, field));
+
+        if let Some(next_hit) = it.next() {
+            *next = next_hit;
+        } else {
+            iterators.pop();
+        }
+
+        if let Some(key) = &key {
+            if let Some(&existing) = seen.get(key) {
+                let existing = &mut hits[existing];
+                if existing.index_uid != index_uid && !existing.matched_index_uids.contains(&index_uid) {
+                    existing.matched_index_uids.push(index_uid);
+                }
+                continue;
+            }
+        }
+
+        let weighted_ranking_score = weighted_ranking_score(hit.ranking_score, weight);
+        if let Some(key) = key {
+            seen.insert(key, hits.len());
+        }
+        hits.push(SearchHitWithIndex {
+            index_uid,
+            weighted_ranking_score,
+            matched_index_uids: Vec::new(),
+            hit,
+        });
+    }
+    hits
+}
+
+/// A hit's `ranking_score` scaled by its query's weight, used by `merge_by_normalized_score` to
+/// order hits across indexes so a more authoritative index can be biased ahead of the others.
+fn weighted_score(ranking_score: Option<f64>, weight: f64) -> f64 {
+    ranking_score.unwrap_or(0.0) * weight
+}
+
+/// The weighted score to surface on [`SearchHitWithIndex::weighted_ranking_score`]. `None` when
+/// `weight` is the default `1.0`, since then the weighted score is just the raw `ranking_score` and
+/// surfacing it would be redundant.
+fn weighted_ranking_score(ranking_score: Option<f64>, weight: f64) -> Option<f64> {
+    (weight != 1.0).then(|| weighted_score(ranking_score, weight))
+}
+
+/// Extracts the `dedupBy` field's value out of a hit's document, stringified so it can be used as a
+/// `HashMap` key regardless of its JSON type. Missing attributes don't participate in dedup.
+fn dedup_key(hit: &SearchHit, field: &str) -> Option<String> {
+    dedup_key_from_document(&hit.document, field)
+}
+
+/// The pure core of [`dedup_key`], operating directly on a document map so it can be unit-tested
+/// without needing a full `SearchHit`.
+fn dedup_key_from_document(document: &serde_json::Map<String, serde_json::Value>, field: &str) -> Option<String> {
+    document.get(field).map(|value| value.to_string())
+}
+
+/// Merge federated hits by comparing their ranking-rule `score_details`, rule by rule, instead of
+/// the single collapsed `ranking_score` used by [`merge_by_normalized_score`]. This preserves the
+/// resolution lost when two hits tie on the normalized score but differ on the rules that produced
+/// it (e.g. one wins on `words` where the other wins on `typo`).
+///
+/// Indexes that don't share the same ranking-rule configuration can't be compared rule-by-rule, so
+/// we fall back to `ranking_score` whenever the two hits' rule sets don't line up.
+///
+/// When `dedup_by` is set, hits are consumed in descending score-details order, so the first
+/// occurrence of a given key is always the highest-ranked one, same as [`merge_by_normalized_score`]:
+/// later occurrences are folded into it instead of appearing as separate hits, recorded in
+/// [`SearchHitWithIndex::matched_index_uids`].
+fn merge_by_score_details(
+    search_results: &[SearchResultWithIndex],
+    dedup_by: Option<&str>,
     max_hits: usize,
 ) -> Vec<SearchHitWithIndex> {
     let mut iterators: Vec<_> = search_results
@@ -157,27 +412,195 @@ fn merge_by_normalized_score(
         })
         .collect();
 
-    let mut hits = Vec::with_capacity(max_hits);
+    let mut hits: Vec<SearchHitWithIndex> = Vec::with_capacity(max_hits);
+    let mut seen: HashMap<String, usize> = HashMap::new();
 
-    for _ in 0..max_hits {
-        iterators.sort_by_key(|(_, _, peeked)| peeked.ranking_score.unwrap());
+    while hits.len() < max_hits && !iterators.is_empty() {
+        iterators.sort_by(|(_, _, a), (_, _, b)| compare_by_score_details(a, b));
 
         let Some((index_uid, it, next)) = iterators.last_mut()
         else {
             break;
         };
 
-        let hit = SearchHitWithIndex { index_uid: index_uid.clone(), hit: next.clone() };
+        let index_uid = index_uid.clone();
+        let hit = next.clone();
+        let key = dedup_by.and_then(|field| dedup_key(&hit, field));
+
         if let Some(next_hit) = it.next() {
             *next = next_hit;
         } else {
             iterators.pop();
         }
-        hits.push(hit);
+
+        if let Some(key) = &key {
+            if let Some(&existing) = seen.get(key) {
+                let existing = &mut hits[existing];
+                if existing.index_uid != index_uid && !existing.matched_index_uids.contains(&index_uid) {
+                    existing.matched_index_uids.push(index_uid);
+                }
+                continue;
+            }
+        }
+
+        if let Some(key) = key {
+            seen.insert(key, hits.len());
+        }
+        hits.push(SearchHitWithIndex {
+            index_uid,
+            weighted_ranking_score: None,
+            matched_index_uids: Vec::new(),
+            hit,
+        });
     }
     hits
 }
 
+/// Merges federated hits with Reciprocal Rank Fusion: each hit's contribution is `1 / (k + rank +
+/// 1)`, where `rank` is its 0-based position in its own index's result list. Unlike
+/// [`merge_by_normalized_score`], RRF never looks at the raw `ranking_score`, which makes it robust
+/// to indexes whose score scales aren't comparable.
+///
+/// Identical documents surfaced by several indexes are fused into a single entry (summing their
+/// contributions) rather than appearing once per index, and the indexes they were found in besides
+/// the surviving copy's are recorded in [`SearchHitWithIndex::matched_index_uids`]. Without
+/// `dedup_by`, two hits are only considered the same document when they come from the same index
+/// and carry the same document payload, so fusion effectively only happens within a single index;
+/// ties are broken by whichever copy ranked best.
+fn merge_by_reciprocal_rank(
+    search_results: &[SearchResultWithIndex],
+    max_hits: usize,
+    k: f64,
+    dedup_by: Option<&str>,
+) -> Vec<SearchHitWithIndex> {
+    struct Fused {
+        index_uid: String,
+        hit: SearchHit,
+        score: f64,
+        best_rank: usize,
+        other_index_uids: Vec<String>,
+    }
+
+    let mut fused: HashMap<String, Fused> = HashMap::new();
+
+    for SearchResultWithIndex { index_uid, result } in search_results {
+        for (rank, hit) in result.hits.iter().enumerate() {
+            let key = match dedup_by.and_then(|field| dedup_key(hit, field)) {
+                Some(key) => key,
+                None => {
+                    format!("{index_uid}:{}", serde_json::to_string(&hit.document).unwrap_or_default())
+                }
+            };
+            let contribution = rrf_contribution(k, rank);
+
+            fused
+                .entry(key)
+                .and_modify(|entry| {
+                    entry.score += contribution;
+                    if index_uid != &entry.index_uid && !entry.other_index_uids.contains(index_uid)
+                    {
+                        entry.other_index_uids.push(index_uid.clone());
+                    }
+                    if rank < entry.best_rank {
+                        entry.best_rank = rank;
+                    }
+                })
+                .or_insert_with(|| Fused {
+                    index_uid: index_uid.clone(),
+                    hit: hit.clone(),
+                    score: contribution,
+                    best_rank: rank,
+                    other_index_uids: Vec::new(),
+                });
+        }
+    }
+
+    let mut fused: Vec<_> = fused.into_values().collect();
+    fused.sort_by(|a, b| compare_fused_rrf(a.score, a.best_rank, b.score, b.best_rank));
+    fused.truncate(max_hits);
+
+    fused
+        .into_iter()
+        .map(|f| SearchHitWithIndex {
+            index_uid: f.index_uid,
+            weighted_ranking_score: None,
+            matched_index_uids: f.other_index_uids,
+            hit: f.hit,
+        })
+        .collect()
+}
+
+/// The Reciprocal Rank Fusion contribution of a hit at 0-based `rank`, given the `k` constant.
+fn rrf_contribution(k: f64, rank: usize) -> f64 {
+    1.0 / (k + rank as f64 + 1.0)
+}
+
+/// Orders two fused RRF entries by descending fused score, breaking ties by whichever ranked best
+/// (lowest `best_rank`) in its own sub-query.
+fn compare_fused_rrf(a_score: f64, a_best_rank: usize, b_score: f64, b_best_rank: usize) -> Ordering {
+    b_score.partial_cmp(&a_score).unwrap_or(Ordering::Equal).then_with(|| a_best_rank.cmp(&b_best_rank))
+}
+
+/// Compares two hits rule-by-rule over their `score_details`. Falls back to comparing the
+/// collapsed `ranking_score` when the two hits don't cover the same rules in the same order (e.g.
+/// they come from indexes with different ranking-rule settings).
+fn compare_by_score_details(a: &SearchHit, b: &SearchHit) -> Ordering {
+    match (&a.ranking_score_details, &b.ranking_score_details) {
+        (Some(a_details), Some(b_details)) => compare_score_detail_maps(a_details, b_details)
+            .unwrap_or_else(|| compare_by_ranking_score(a, b)),
+        _ => compare_by_ranking_score(a, b),
+    }
+}
+
+/// Compares two `score_details` maps rule-by-rule, in ranking-rule order: rules are ordered by
+/// their `order` field (then by name, for rules without one), so the result doesn't depend on the
+/// maps' iteration order. Returns `None` when the two maps don't cover the same rules in the same
+/// order.
+fn compare_score_detail_maps(
+    a_details: &serde_json::Map<String, serde_json::Value>,
+    b_details: &serde_json::Map<String, serde_json::Value>,
+) -> Option<Ordering> {
+    let rule_order = |details: &serde_json::Map<String, serde_json::Value>, rule: &str| {
+        details.get(rule).and_then(|details| details.get("order")).and_then(|v| v.as_u64())
+    };
+    let mut a_rules: Vec<&String> = a_details.keys().collect();
+    let mut b_rules: Vec<&String> = b_details.keys().collect();
+    a_rules.sort_by_key(|rule| (rule_order(a_details, rule.as_str()), *rule));
+    b_rules.sort_by_key(|rule| (rule_order(b_details, rule.as_str()), *rule));
+    if a_rules != b_rules {
+        return None;
+    }
+
+    for rule in a_rules {
+        let a_score = a_details.get(rule).and_then(|details| details.get("score")).and_then(|v| v.as_f64());
+        let b_score = b_details.get(rule).and_then(|details| details.get("score")).and_then(|v| v.as_f64());
+        match a_score.partial_cmp(&b_score) {
+            Some(Ordering::Equal) | None => continue,
+            Some(ord) => return Some(ord),
+        }
+    }
+    Some(Ordering::Equal)
+}
+
+fn compare_by_ranking_score(a: &SearchHit, b: &SearchHit) -> Ordering {
+    a.ranking_score
+        .unwrap_or(0.0)
+        .partial_cmp(&b.ranking_score.unwrap_or(0.0))
+        .unwrap_or(Ordering::Equal)
+}
+
+/// Aborts every sub-query task spawned so far, called when a later query fails its auth or
+/// index-lookup check and we're about to discard the whole response. This only helps for tasks
+/// that haven't started running on the blocking pool yet; `JoinHandle::abort` on a
+/// `spawn_blocking` task that's already executing `perform_search` does not interrupt it; it only
+/// drops the now-unused result once that search finishes. Still worth doing: it's free, and it
+/// does skip the queued-but-not-yet-running tasks.
+fn abort_all<I, T>(tasks: &[(usize, I, f64, tokio::task::JoinHandle<T>)]) {
+    for (.., task) in tasks {
+        task.abort();
+    }
+}
+
 /// Local `Result` extension trait to avoid `map_err` boilerplate.
 trait WithIndex {
     type T;
@@ -191,3 +614,99 @@ impl<T, E: Into<ResponseError>> WithIndex for Result<T, E> {
         self.map_err(|err| (err.into(), index))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn score_details_compare_ignores_map_iteration_order() {
+        let a = json!({"words": {"score": 1.0}, "typo": {"score": 0.5}}).as_object().unwrap().clone();
+        let b = json!({"typo": {"score": 0.5}, "words": {"score": 1.0}}).as_object().unwrap().clone();
+        assert_eq!(compare_score_detail_maps(&a, &b), Some(Ordering::Equal));
+    }
+
+    #[test]
+    fn score_details_compare_falls_back_when_rule_sets_differ() {
+        let a = json!({"words": {"score": 1.0}}).as_object().unwrap().clone();
+        let b = json!({"typo": {"score": 1.0}}).as_object().unwrap().clone();
+        assert_eq!(compare_score_detail_maps(&a, &b), None);
+    }
+
+    #[test]
+    fn rrf_contribution_decreases_with_rank() {
+        assert_eq!(rrf_contribution(60.0, 0), 1.0 / 61.0);
+        assert!(rrf_contribution(60.0, 0) > rrf_contribution(60.0, 1));
+    }
+
+    #[test]
+    fn rrf_tie_break_prefers_best_rank_on_equal_score() {
+        assert_eq!(compare_fused_rrf(1.0, 2, 1.0, 0), Ordering::Greater);
+        assert_eq!(compare_fused_rrf(1.0, 0, 1.0, 2), Ordering::Less);
+    }
+
+    #[test]
+    fn rrf_prefers_higher_fused_score_over_rank() {
+        assert_eq!(compare_fused_rrf(2.0, 5, 1.0, 0), Ordering::Less);
+    }
+
+    #[test]
+    fn dedup_key_missing_field_is_none() {
+        let document = json!({"id": 1}).as_object().unwrap().clone();
+        assert_eq!(dedup_key_from_document(&document, "sku"), None);
+    }
+
+    #[test]
+    fn dedup_key_collision_on_matching_field_value() {
+        let a = json!({"sku": "ABC-1", "title": "Widget (EN)"}).as_object().unwrap().clone();
+        let b = json!({"sku": "ABC-1", "title": "Gadget (FR)"}).as_object().unwrap().clone();
+        assert_eq!(dedup_key_from_document(&a, "sku"), dedup_key_from_document(&b, "sku"));
+    }
+
+    #[test]
+    fn dedup_key_differs_for_different_field_values() {
+        let a = json!({"sku": "ABC-1"}).as_object().unwrap().clone();
+        let b = json!({"sku": "ABC-2"}).as_object().unwrap().clone();
+        assert_ne!(dedup_key_from_document(&a, "sku"), dedup_key_from_document(&b, "sku"));
+    }
+
+    #[test]
+    fn federation_window_on_empty_queries_has_zero_max_hits() {
+        assert_eq!(federation_window(None, None, &[]), (0, 0, 0));
+    }
+
+    #[test]
+    fn federation_window_defaults_limit_to_widest_per_query_limit() {
+        assert_eq!(federation_window(None, None, &[5, 20, 10]), (0, 20, 20));
+    }
+
+    #[test]
+    fn federation_window_prefers_explicit_offset_and_limit() {
+        assert_eq!(federation_window(Some(20), Some(20), &[5, 20, 10]), (20, 20, 40));
+    }
+
+    #[test]
+    fn paginate_slices_by_offset_and_limit() {
+        assert_eq!(paginate(vec![1, 2, 3, 4, 5], 1, 2), vec![2, 3]);
+        assert_eq!(paginate(vec![1, 2, 3], 10, 5), Vec::<i32>::new());
+    }
+
+    #[test]
+    fn higher_weight_can_beat_higher_raw_ranking_score() {
+        let low_weighted_high_score = weighted_score(Some(0.9), 1.0);
+        let high_weighted_low_score = weighted_score(Some(0.5), 2.0);
+        assert!(high_weighted_low_score > low_weighted_high_score);
+    }
+
+    #[test]
+    fn weighted_ranking_score_is_none_at_default_weight() {
+        assert_eq!(weighted_ranking_score(Some(0.9), 1.0), None);
+    }
+
+    #[test]
+    fn weighted_ranking_score_is_some_at_non_default_weight() {
+        assert_eq!(weighted_ranking_score(Some(0.5), 2.0), Some(1.0));
+    }
+}